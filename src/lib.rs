@@ -0,0 +1,819 @@
+//! Core logic for pipewire-sample-rate-switcher, split out of `main` so it
+//! can be reused (e.g. from a tray applet) and exercised in tests without
+//! going through `process::exit`.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use notify_rust::{Hint, Notification, Timeout};
+use regex::Regex;
+use serde_json::Value;
+
+// How long a newly negotiated rate has to stay put before `watch_and_follow`
+// acts on it. Formats renegotiate in quick bursts while a stream starts up,
+// so this avoids chasing every intermediate value.
+pub const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Every way this crate's operations can fail, in place of the old
+/// `panic!`/`process::exit(1)` calls.
+#[derive(Debug, thiserror::Error)]
+pub enum SwitcherError {
+    #[error("HOME is not set")]
+    HomeNotSet,
+
+    #[error("failed to read {path}: {source}")]
+    ConfigNotFound {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("marker '{0}' not found in config")]
+    MarkerMissing(String),
+
+    #[error("options end marker appears before start marker")]
+    MarkerOrder,
+
+    #[error(
+        "could not find a line like '# Sample Rate Options = 44100, 48000' in the options block"
+    )]
+    OptionsLineMissing,
+
+    #[error("no sample-rate numbers found on options line: {0}")]
+    NoRates(String),
+
+    #[error("invalid TOML config: {0}")]
+    InvalidToml(#[from] toml::de::Error),
+
+    #[error("config.toml must set a non-empty `rates` array")]
+    EmptyRates,
+
+    #[error("config.toml's `{field}` value ({value}) doesn't fit in a u32")]
+    InvalidClockValue { field: &'static str, value: i64 },
+
+    #[error("failed to write {path}: {source}")]
+    WriteFailed {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to exec systemctl: {0}")]
+    RestartExecFailed(#[source] io::Error),
+
+    #[error("PipeWire/WirePlumber restart failed (even after socket bounce)")]
+    RestartFailed,
+
+    #[error("pw-metadata not available: {0}")]
+    PwMetadataUnavailable(#[source] io::Error),
+
+    #[error("pw-metadata {key} exited with {status}: {stderr}")]
+    PwMetadataFailed {
+        key: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+
+    #[error("failed to spawn pw-dump --monitor: {0}")]
+    PwDumpSpawnFailed(#[source] io::Error),
+
+    #[error("pw-dump gave us no stdout")]
+    PwDumpNoStdout,
+
+    #[error("pw-dump --monitor exited")]
+    PwDumpExited,
+}
+
+/* ------------------------- Paths ------------------------- */
+
+pub fn default_sway_config() -> Result<PathBuf, SwitcherError> {
+    Ok(
+        PathBuf::from(env::var("HOME").map_err(|_| SwitcherError::HomeNotSet)?)
+            .join(".config/sway/config"),
+    )
+}
+
+pub fn default_toml_config() -> Result<PathBuf, SwitcherError> {
+    Ok(
+        PathBuf::from(env::var("HOME").map_err(|_| SwitcherError::HomeNotSet)?)
+            .join(".config/pipewire-sample-rate-switcher/config.toml"),
+    )
+}
+
+pub fn default_samplerate_conf() -> Result<PathBuf, SwitcherError> {
+    Ok(
+        PathBuf::from(env::var("HOME").map_err(|_| SwitcherError::HomeNotSet)?)
+            .join(".config/pipewire/pipewire.conf.d/99-samplerate.conf"),
+    )
+}
+
+/// Honor an explicit `--config`; otherwise prefer the native TOML config
+/// when it exists and fall back to the legacy sway config so existing
+/// setups keep working untouched.
+pub fn resolve_config_path(explicit: Option<PathBuf>) -> Result<PathBuf, SwitcherError> {
+    if let Some(p) = explicit {
+        return Ok(p);
+    }
+    let toml_path = default_toml_config()?;
+    if toml_path.exists() {
+        Ok(toml_path)
+    } else {
+        default_sway_config()
+    }
+}
+
+/* ------------------------- Parsing ------------------------- */
+
+/// Rates parsed from whichever config source was in play, plus the bits
+/// that only the TOML format can express.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedConfig {
+    pub rates: Vec<u32>,
+    pub allowed_rates: Vec<u32>,
+    pub default: Option<u32>,
+    pub clock: ClockExtras,
+}
+
+/// Optional `context.properties` clock knobs beyond `default.clock.rate`
+/// and `default.clock.allowed-rates`. Each field is only written to the
+/// canonical conf when set, so the file stays minimal for users who don't
+/// care about buffer sizing or forcing the rate.
+///
+/// There's deliberately no "forced sample format" knob here: PipeWire has
+/// no global `default.clock.*` property for that — sample format is
+/// negotiated per-node/device (e.g. via WirePlumber ALSA monitor rules), so
+/// faking one here would silently do nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClockExtras {
+    pub quantum: Option<u32>,
+    pub min_quantum: Option<u32>,
+    pub max_quantum: Option<u32>,
+    pub force_rate: bool,
+}
+
+/// Load options from `path`, auto-detecting TOML vs. the legacy sway
+/// format by file extension.
+pub fn parse_options(path: &Path) -> Result<ParsedConfig, SwitcherError> {
+    let content = fs::read_to_string(path).map_err(|e| SwitcherError::ConfigNotFound {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+
+    if path.extension().and_then(|e| e.to_str()) == Some("toml") {
+        parse_toml_config(&content)
+    } else {
+        let rates = parse_options_from_sway(
+            &content,
+            "Pipewire Sample Rate Options Start",
+            "Pipewire Sample Rate Options End",
+        )?;
+        Ok(ParsedConfig {
+            allowed_rates: rates.clone(),
+            rates,
+            default: None,
+            clock: ClockExtras::default(),
+        })
+    }
+}
+
+// Narrow a TOML integer down to u32, rejecting negative or oversized values
+// instead of silently wrapping them via `as`.
+fn toml_u32(field: &'static str, value: i64) -> Result<u32, SwitcherError> {
+    u32::try_from(value).map_err(|_| SwitcherError::InvalidClockValue { field, value })
+}
+
+fn toml_u32_array(
+    doc: &toml::Value,
+    field: &'static str,
+) -> Result<Option<Vec<u32>>, SwitcherError> {
+    doc.get(field)
+        .and_then(toml::Value::as_array)
+        .map(|arr| {
+            arr.iter()
+                .filter_map(toml::Value::as_integer)
+                .map(|v| toml_u32(field, v))
+                .collect::<Result<Vec<u32>, _>>()
+        })
+        .transpose()
+}
+
+fn toml_u32_scalar(doc: &toml::Value, field: &'static str) -> Result<Option<u32>, SwitcherError> {
+    doc.get(field)
+        .and_then(toml::Value::as_integer)
+        .map(|v| toml_u32(field, v))
+        .transpose()
+}
+
+/// Parse `~/.config/pipewire-sample-rate-switcher/config.toml`:
+///
+/// ```text
+/// rates = [44100, 48000, 88200, 96000]
+/// default = 48000
+/// allowed_rates = [44100, 48000, 88200, 96000]
+/// quantum = 1024
+/// min_quantum = 32
+/// max_quantum = 2048
+/// force_rate = true
+/// ```
+///
+/// Everything but `rates` is optional; `allowed_rates` falls back to
+/// `rates` when absent.
+pub fn parse_toml_config(content: &str) -> Result<ParsedConfig, SwitcherError> {
+    let doc: toml::Value = content.parse()?;
+
+    let mut rates = toml_u32_array(&doc, "rates")?
+        .filter(|v: &Vec<u32>| !v.is_empty())
+        .ok_or(SwitcherError::EmptyRates)?;
+    rates.sort_unstable();
+    rates.dedup();
+
+    let default = toml_u32_scalar(&doc, "default")?;
+
+    let allowed_rates = toml_u32_array(&doc, "allowed_rates")?
+        .filter(|v: &Vec<u32>| !v.is_empty())
+        .unwrap_or_else(|| rates.clone());
+
+    let clock = ClockExtras {
+        quantum: toml_u32_scalar(&doc, "quantum")?,
+        min_quantum: toml_u32_scalar(&doc, "min_quantum")?,
+        max_quantum: toml_u32_scalar(&doc, "max_quantum")?,
+        force_rate: doc
+            .get("force_rate")
+            .and_then(toml::Value::as_bool)
+            .unwrap_or(false),
+    };
+
+    Ok(ParsedConfig {
+        rates,
+        allowed_rates,
+        default,
+        clock,
+    })
+}
+
+/// Fallback parser for the legacy `~/.config/sway/config` marker block:
+///
+/// ```text
+/// # Pipewire Sample Rate Options Start
+/// # Sample Rate Options = 44100, 48000
+/// # Pipewire Sample Rate Options End
+/// ```
+pub fn parse_options_from_sway(
+    content: &str,
+    start_marker: &str,
+    end_marker: &str,
+) -> Result<Vec<u32>, SwitcherError> {
+    let lines: Vec<&str> = content.lines().collect();
+    let start_idx = lines
+        .iter()
+        .position(|l| l.contains(start_marker))
+        .ok_or_else(|| SwitcherError::MarkerMissing(start_marker.to_string()))?;
+    let end_idx = lines
+        .iter()
+        .position(|l| l.contains(end_marker))
+        .ok_or_else(|| SwitcherError::MarkerMissing(end_marker.to_string()))?;
+    if end_idx <= start_idx {
+        return Err(SwitcherError::MarkerOrder);
+    }
+
+    // Find: "# Sample Rate Options = 44100, 48000"
+    let opt_line = lines[start_idx..=end_idx]
+        .iter()
+        .find(|l| l.trim_start().starts_with("# Sample Rate Options ="))
+        .ok_or(SwitcherError::OptionsLineMissing)?;
+
+    let re_num = Regex::new(r"(\d{4,5})").unwrap();
+    let mut options: Vec<u32> = re_num
+        .captures_iter(opt_line)
+        .filter_map(|c| c.get(1).and_then(|m| m.as_str().parse::<u32>().ok()))
+        .collect();
+
+    if options.is_empty() {
+        return Err(SwitcherError::NoRates(opt_line.to_string()));
+    }
+    options.sort_unstable();
+    options.dedup();
+    Ok(options)
+}
+
+pub fn next_rate(options: &[u32], current: u32) -> u32 {
+    if let Some(i) = options.iter().position(|&r| r == current) {
+        options[(i + 1) % options.len()]
+    } else {
+        options[0]
+    }
+}
+
+/* ------------------------- File read/write ------------------------- */
+
+pub fn read_rate_from_file(path: &Path) -> Option<u32> {
+    let s = fs::read_to_string(path).ok()?;
+    // Looser match: find rate anywhere, even if it's on the same line as the '{'
+    let re = Regex::new(r#"default\.clock\.rate\s*=\s*"?(\d{4,5})"?"#).ok()?;
+    let caps = re.captures(&s)?;
+    caps.get(1)?.as_str().parse::<u32>().ok()
+}
+
+pub fn write_canonical_samplerate_conf(
+    path: &Path,
+    new_rate: u32,
+    allowed_all: &[u32],
+    clock: &ClockExtras,
+) -> Result<(), SwitcherError> {
+    let write_err = |source: io::Error| SwitcherError::WriteFailed {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(write_err)?;
+    }
+
+    let mut v = allowed_all.to_vec();
+    v.sort_unstable();
+    v.dedup();
+    let allowed_bracket = format!(
+        "[ {} ]",
+        v.iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
+    let mut text = format!(
+        "context.properties = {{\n    default.clock.rate          = {}\n    default.clock.allowed-rates = {}\n",
+        new_rate, allowed_bracket
+    );
+
+    if let Some(q) = clock.quantum {
+        text.push_str(&format!("    default.clock.quantum        = {}\n", q));
+    }
+    if let Some(q) = clock.min_quantum {
+        text.push_str(&format!("    default.clock.min-quantum    = {}\n", q));
+    }
+    if let Some(q) = clock.max_quantum {
+        text.push_str(&format!("    default.clock.max-quantum    = {}\n", q));
+    }
+    if clock.force_rate {
+        text.push_str(&format!(
+            "    default.clock.force-rate     = {}\n",
+            new_rate
+        ));
+    }
+    text.push_str("}\n");
+
+    fs::write(path, text).map_err(write_err)
+}
+
+/* ------------------------- Restart helpers ------------------------- */
+
+pub fn restart_pipewire_stack() -> Result<(), SwitcherError> {
+    // Try a straight restart first
+    let status = Command::new("systemctl")
+        .args([
+            "--user",
+            "restart",
+            "pipewire.service",
+            "pipewire-pulse.service",
+            "wireplumber.service",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(SwitcherError::RestartExecFailed)?;
+
+    if status.success() {
+        return Ok(());
+    }
+
+    // Fallback: stop socket, then start services and socket again
+    let _ = Command::new("systemctl")
+        .args(["--user", "stop", "pipewire.socket"])
+        .status();
+
+    let ok_pw = Command::new("systemctl")
+        .args(["--user", "start", "pipewire.service"])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+
+    let _ = Command::new("systemctl")
+        .args(["--user", "start", "pipewire.socket"])
+        .status();
+
+    let ok_wp = Command::new("systemctl")
+        .args(["--user", "restart", "wireplumber.service"])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+
+    if ok_pw && ok_wp {
+        Ok(())
+    } else {
+        Err(SwitcherError::RestartFailed)
+    }
+}
+
+/* ------------------------- Optional: read current graph rate (info only) ------------------------- */
+
+pub fn read_graph_rate_quick() -> Option<u32> {
+    let out = Command::new("pw-metadata")
+        .args(["-n", "settings", "0", "clock.rate"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let s = String::from_utf8_lossy(&out.stdout);
+    let re = Regex::new(r"(\d{4,5})").ok()?;
+    re.captures(&s)?.get(1)?.as_str().parse::<u32>().ok()
+}
+
+pub fn read_graph_quantum_quick() -> Option<u32> {
+    let out = Command::new("pw-metadata")
+        .args(["-n", "settings", "0", "clock.quantum"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let s = String::from_utf8_lossy(&out.stdout);
+    let re = Regex::new(r"(\d+)").ok()?;
+    re.captures(&s)?.get(1)?.as_str().parse::<u32>().ok()
+}
+
+/* ------------------------- Live switch (no restart) ------------------------- */
+
+/// Switch the running graph's rate in place via `pw-metadata`, the same
+/// interface `read_graph_rate_quick` reads from. Returns `Err` if the
+/// binary is missing or exits nonzero, so callers can fall back to the
+/// file-edit-plus-restart path.
+pub fn switch_rate_live(new_rate: u32) -> Result<(), SwitcherError> {
+    run_pw_metadata_set("clock.force-rate", new_rate)?;
+    // Best-effort: also nudge clock.rate so clients that only watch that key
+    // see the change. Failure here doesn't invalidate the force-rate switch.
+    let _ = run_pw_metadata_set("clock.rate", new_rate);
+    Ok(())
+}
+
+fn run_pw_metadata_set(key: &str, value: u32) -> Result<(), SwitcherError> {
+    let out = Command::new("pw-metadata")
+        .args(["-n", "settings", "0", key, &value.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(SwitcherError::PwMetadataUnavailable)?;
+
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(SwitcherError::PwMetadataFailed {
+            key: key.to_string(),
+            status: out.status,
+            stderr: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        })
+    }
+}
+
+/// Outcome of [`switch_rate`], so callers can report what actually happened.
+#[derive(Debug)]
+pub enum SwitchOutcome {
+    /// Applied instantly via `pw-metadata`, no restart needed.
+    Live,
+    /// The live switch failed, so we fell back to a full restart.
+    RestartedAfterLiveFailure(SwitcherError),
+    /// `--restart` was requested explicitly.
+    Restarted,
+}
+
+/// Persist `next` to `samplerate_conf` and apply it: live via `pw-metadata`
+/// unless `want_restart` is set, falling back to a full PipeWire/WirePlumber
+/// restart if the live switch isn't available.
+pub fn switch_rate(
+    samplerate_conf: &Path,
+    next: u32,
+    allowed_rates: &[u32],
+    clock: &ClockExtras,
+    want_restart: bool,
+) -> Result<SwitchOutcome, SwitcherError> {
+    write_canonical_samplerate_conf(samplerate_conf, next, allowed_rates, clock)?;
+
+    if !want_restart {
+        match switch_rate_live(next) {
+            Ok(()) => return Ok(SwitchOutcome::Live),
+            Err(live_err) => {
+                restart_pipewire_stack()?;
+                return Ok(SwitchOutcome::RestartedAfterLiveFailure(live_err));
+            }
+        }
+    }
+
+    restart_pipewire_stack()?;
+    Ok(SwitchOutcome::Restarted)
+}
+
+/* ------------------------- Watch mode (bit-perfect follow) ------------------------- */
+
+/// Follow whatever rate the currently playing stream actually negotiated
+/// and keep the graph's rate matched to it, so e.g. a 44.1 kHz track plays
+/// at 44100 and a 96 kHz track at 96000 without manual toggling. Never
+/// returns on success; only stops if `pw-dump` itself can't be spawned or
+/// its output stream closes.
+///
+/// `pw-dump --monitor` only emits when the graph actually changes, not on
+/// a timer, so reading it has to happen on its own thread: the main loop
+/// below needs `recv_timeout` to wake up on `WATCH_DEBOUNCE` even while the
+/// graph is quiet, otherwise a rate that stabilizes and then goes silent
+/// would leave `pending` stuck forever.
+pub fn watch_and_follow(options: &[u32]) -> Result<(), SwitcherError> {
+    let mut child = Command::new("pw-dump")
+        .arg("--monitor")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(SwitcherError::PwDumpSpawnFailed)?;
+
+    let stdout = child.stdout.take().ok_or(SwitcherError::PwDumpNoStdout)?;
+
+    let (tx, rx) = mpsc::channel::<Value>();
+    thread::spawn(move || {
+        // `.flatten()` drops a parse error, but serde_json's stream
+        // deserializer yields nothing after one, so non-JSON output ends this
+        // thread and with it the watch loop (PwDumpExited).
+        for v in serde_json::Deserializer::from_reader(stdout)
+            .into_iter::<Value>()
+            .flatten()
+        {
+            if tx.send(v).is_err() {
+                break; // watch_and_follow gave up on us
+            }
+        }
+    });
+
+    let mut current = read_graph_rate_quick();
+    let mut pending: Option<(u32, Instant)> = None;
+
+    println!(
+        "Watching for stream rate changes (allowed rates: {:?})...",
+        options
+    );
+
+    loop {
+        match rx.recv_timeout(WATCH_DEBOUNCE) {
+            Ok(dump) => {
+                if let Some(negotiated) = find_active_stream_rate(&dump) {
+                    if !options.contains(&negotiated) {
+                        // never force a rate we weren't told to support
+                    } else if Some(negotiated) == current {
+                        pending = None;
+                    } else if pending.as_ref().map(|(rate, _)| *rate) != Some(negotiated) {
+                        pending = Some((negotiated, Instant::now()));
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {} // fall through and re-check `pending` below
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if let Some((rate, since)) = pending {
+            if since.elapsed() >= WATCH_DEBOUNCE {
+                match switch_rate_live(rate) {
+                    Ok(()) => {
+                        println!("Watch: following stream -> {} Hz.", rate);
+                        current = Some(rate);
+                    }
+                    Err(e) => eprintln!("Watch: failed to switch to {rate}: {e}."),
+                }
+                pending = None;
+            }
+        }
+    }
+
+    let _ = child.wait();
+    Err(SwitcherError::PwDumpExited)
+}
+
+// Walk a `pw-dump` snapshot for the rate an actively-running audio output
+// stream negotiated. SPA pod JSON nests the same logical fields differently
+// across PipeWire versions, so rather than modelling every shape we just
+// look for a node that looks like a running playback stream (not a
+// monitor/loopback) and pull the first "audio.rate"/"rate" value out of its
+// params.
+fn find_active_stream_rate(dump: &Value) -> Option<u32> {
+    let nodes = dump.as_array()?;
+    for node in nodes {
+        if node.get("type").and_then(Value::as_str) != Some("PipeWire:Interface:Node") {
+            continue;
+        }
+        let info = node.get("info")?;
+        if info.get("state").and_then(Value::as_str) != Some("running") {
+            continue;
+        }
+
+        let props = info.get("props").cloned().unwrap_or(Value::Null);
+        let media_class = props
+            .get("media.class")
+            .and_then(Value::as_str)
+            .unwrap_or("");
+        if !media_class.contains("Stream/Output/Audio") || media_class.contains("Monitor") {
+            continue;
+        }
+        let node_name = props.get("node.name").and_then(Value::as_str).unwrap_or("");
+        if node_name.to_lowercase().contains("monitor")
+            || node_name.to_lowercase().contains("loopback")
+        {
+            continue;
+        }
+
+        if let Some(params) = info.get("params") {
+            if let Some(rate) = find_rate_in_params(params) {
+                return Some(rate);
+            }
+        }
+    }
+    None
+}
+
+fn find_rate_in_params(params: &Value) -> Option<u32> {
+    match params {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if (key == "audio.rate" || key == "rate") && value.is_u64() {
+                    return value.as_u64().map(|v| v as u32);
+                }
+                if let Some(rate) = find_rate_in_params(value) {
+                    return Some(rate);
+                }
+            }
+            None
+        }
+        Value::Array(items) => items.iter().find_map(find_rate_in_params),
+        _ => None,
+    }
+}
+
+/* ------------------------- Notifications ------------------------- */
+
+pub fn notify_ok(from: u32, to: u32) {
+    let _ = Notification::new()
+        .summary("Pipewire Sample Rate Switcher")
+        .body(&format!(
+            "Switched default.clock.rate: {} -> {} Hz.",
+            from, to
+        ))
+        .icon("audio-card")
+        .appname("pipewire-sample-rate-switcher")
+        .hint(Hint::Category("Device".to_owned()))
+        .timeout(Timeout::Milliseconds(6000))
+        .show();
+}
+
+pub fn notify_err(msg: &str) {
+    let _ = Notification::new()
+        .summary("Pipewire Sample Rate Switcher — Error")
+        .body(msg)
+        .icon("dialog-error")
+        .appname("pipewire-sample-rate-switcher")
+        .timeout(Timeout::Milliseconds(8000))
+        .show();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn next_rate_cycles_and_wraps() {
+        let options = [44100, 48000, 96000];
+        assert_eq!(next_rate(&options, 44100), 48000);
+        assert_eq!(next_rate(&options, 48000), 96000);
+        assert_eq!(next_rate(&options, 96000), 44100);
+    }
+
+    #[test]
+    fn next_rate_falls_back_to_first_when_current_is_unknown() {
+        let options = [44100, 48000];
+        assert_eq!(next_rate(&options, 88200), 44100);
+    }
+
+    #[test]
+    fn parse_options_from_sway_reads_the_marked_block() {
+        let content = "\
+# Pipewire Sample Rate Options Start
+# Sample Rate Options = 48000, 44100
+# Pipewire Sample Rate Options End
+";
+        let options = parse_options_from_sway(content, "Options Start", "Options End").unwrap();
+        assert_eq!(options, vec![44100, 48000]);
+    }
+
+    #[test]
+    fn parse_options_from_sway_errors_without_markers() {
+        let err =
+            parse_options_from_sway("no markers here", "Options Start", "Options End").unwrap_err();
+        assert!(matches!(err, SwitcherError::MarkerMissing(_)));
+    }
+
+    #[test]
+    fn parse_toml_config_reads_rates_default_and_allowed_rates() {
+        let config = parse_toml_config(
+            "rates = [48000, 44100]\ndefault = 48000\nallowed_rates = [44100, 48000, 96000]\n",
+        )
+        .unwrap();
+        assert_eq!(config.rates, vec![44100, 48000]);
+        assert_eq!(config.default, Some(48000));
+        assert_eq!(config.allowed_rates, vec![44100, 48000, 96000]);
+    }
+
+    #[test]
+    fn parse_toml_config_allowed_rates_falls_back_to_rates() {
+        let config = parse_toml_config("rates = [44100, 48000]\n").unwrap();
+        assert_eq!(config.allowed_rates, config.rates);
+    }
+
+    #[test]
+    fn parse_toml_config_empty_allowed_rates_falls_back_to_rates() {
+        let config = parse_toml_config("rates = [44100, 48000]\nallowed_rates = []\n").unwrap();
+        assert_eq!(config.allowed_rates, config.rates);
+    }
+
+    #[test]
+    fn parse_toml_config_reads_clock_extras() {
+        let config = parse_toml_config(
+            "rates = [44100]\nquantum = 1024\nmin_quantum = 32\nmax_quantum = 2048\nforce_rate = true\n",
+        )
+        .unwrap();
+        assert_eq!(config.clock.quantum, Some(1024));
+        assert_eq!(config.clock.min_quantum, Some(32));
+        assert_eq!(config.clock.max_quantum, Some(2048));
+        assert!(config.clock.force_rate);
+    }
+
+    #[test]
+    fn parse_toml_config_rejects_empty_rates() {
+        let err = parse_toml_config("rates = []\n").unwrap_err();
+        assert!(matches!(err, SwitcherError::EmptyRates));
+    }
+
+    #[test]
+    fn parse_toml_config_rejects_out_of_range_clock_values() {
+        let err = parse_toml_config("rates = [44100]\nquantum = -256\n").unwrap_err();
+        assert!(matches!(
+            err,
+            SwitcherError::InvalidClockValue {
+                field: "quantum",
+                value: -256
+            }
+        ));
+
+        let err = parse_toml_config("rates = [44100]\nmax_quantum = 10000000000\n").unwrap_err();
+        assert!(matches!(
+            err,
+            SwitcherError::InvalidClockValue {
+                field: "max_quantum",
+                value: 10_000_000_000
+            }
+        ));
+    }
+
+    #[test]
+    fn write_canonical_samplerate_conf_includes_only_set_clock_extras() {
+        let path = std::env::temp_dir().join(format!(
+            "pipewire-sample-rate-switcher-test-{:?}.conf",
+            thread::current().id()
+        ));
+
+        write_canonical_samplerate_conf(&path, 48000, &[44100, 48000], &ClockExtras::default())
+            .unwrap();
+        let minimal = fs::read_to_string(&path).unwrap();
+        assert!(minimal.contains("default.clock.rate          = 48000"));
+        assert!(!minimal.contains("quantum"));
+        assert!(!minimal.contains("force-rate"));
+
+        let full_clock = ClockExtras {
+            quantum: Some(1024),
+            min_quantum: Some(32),
+            max_quantum: Some(2048),
+            force_rate: true,
+        };
+        write_canonical_samplerate_conf(&path, 48000, &[44100, 48000], &full_clock).unwrap();
+        let full = fs::read_to_string(&path).unwrap();
+        assert!(full.contains("default.clock.quantum        = 1024"));
+        assert!(full.contains("default.clock.min-quantum    = 32"));
+        assert!(full.contains("default.clock.max-quantum    = 2048"));
+        assert!(full.contains("default.clock.force-rate     = 48000"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}